@@ -1,34 +1,158 @@
+use serde::{Deserialize, Serialize};
+use serde_big_array::BigArray;
+
+#[derive(Serialize, Deserialize)]
 struct World {
     maps:Vec<Map>,
     current_map:usize, // An index into the maps vec.
 }
+#[derive(Serialize, Deserialize)]
 struct Map {
-    tiles:[[Tile; 81]; 23], // An grid of 23 rows of 80 Tiles
+    tiles:[TileRow; 23], // An grid of 23 rows of 81 Tiles
     entities:Vec<Thing>, // We'll assume the player is always the 0th thing in this list
+    doors:Vec<DoorMeta>, // Flavor text and destination for each Tile::Door(id) present, from the level file or built with a generated cave
+}
+
+// A single row of the tile grid. Plain `[Tile; 81]` exceeds the array length serde
+// derives Serialize/Deserialize for directly, so each row goes through `BigArray`
+// instead; `Deref`/`DerefMut` keep `map.tiles[y][x]` working unchanged everywhere else.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct TileRow(#[serde(with = "BigArray")] [Tile; 81]);
+
+impl std::ops::Deref for TileRow {
+    type Target = [Tile; 81];
+    fn deref(&self) -> &[Tile; 81] {
+        &self.0
+    }
 }
-#[derive(Clone,Copy)]
+impl std::ops::DerefMut for TileRow {
+    fn deref_mut(&mut self) -> &mut [Tile; 81] {
+        &mut self.0
+    }
+}
+
+// What happens when the player steps onto a `Tile::Door(id)` matching this `DoorMeta`'s id.
+#[derive(Clone, Serialize, Deserialize)]
+enum DoorKind {
+    // Leads to the next map: the message is logged and the player reappears at `destination`.
+    Passage{message:String, destination:(u8,u8)},
+    // Leads out of the prison entirely and ends the game in victory.
+    Exit,
+}
+#[derive(Clone, Serialize, Deserialize)]
+struct DoorMeta {
+    id:DoorID,
+    kind:DoorKind,
+}
+#[derive(Clone,Copy,Serialize,Deserialize)]
 enum Tile {
     Empty,
     Wall,
-    Key(DoorID), 
-    Door(DoorID), 
+    Key(DoorID),
+    Door(DoorID),
 }
 
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
 struct DoorID(usize);
+#[derive(Serialize, Deserialize)]
 struct Thing {
     position:(u8,u8),
-    thing_type:ThingType
+    thing_type:ThingType,
+    alerted:bool, // Guards start unaware; set once they spot the player and never reset.
+    #[serde(skip)]
+    actions: VecDeque<Action>, // Queued behavior, drained one-at-a-time by Map::step.
+}
+impl Thing {
+    fn new(thing_type:ThingType, position:(u8,u8)) -> Self {
+        Thing { thing_type, position, alerted:false, actions:VecDeque::new() }
+    }
 }
+#[derive(Serialize, Deserialize)]
 enum ThingType {
     Prisoner,
     Guard,
 }
 
+// A single turn's worth of behavior for one `Thing`, drained one-at-a-time by
+// `Map::step` instead of being driven by imperative logic in the event loop.
+// Guards get scripted this way (currently just `Follow`, chasing the player
+// down once alerted). The player's queue holds the moves banked by arrow-key
+// presses since the last Enter, so several can be acted out back-to-back.
+enum Action {
+    Move(i8, i8),
+    Follow(usize),
+}
+
+#[derive(Serialize, Deserialize)]
 struct PrisonerState {
     keys: Vec<DoorID>,
     health: usize,
 }
+impl PrisonerState {
+    // Renders the inventory/health footer rows (27-28), reserved below the message
+    // log. Called after every state change that could affect them -- a move, or a
+    // load -- so they never show stale values from before that change.
+    fn draw_footer(&self, out: &mut std::io::Stdout) -> std::io::Result<()> {
+        use crossterm::{cursor, style::{Colors, Color, SetColors, Print}, ExecutableCommand};
+        let footer_colors = Colors{foreground:Some(Color::Black), background:Some(Color::White)};
+        out.execute(cursor::MoveTo(0, 27))?;
+        out.execute(SetColors(footer_colors))?;
+        let inventory = "Inventory: ".to_string() + &(self.keys.len()).to_string() + " keys                                                               ";
+        out.execute(Print(inventory))?;
+        out.execute(cursor::MoveTo(0, 28))?;
+        out.execute(SetColors(footer_colors))?;
+        let health = "Health: ".to_string() + &(self.health).to_string() + "%                                                                     ";
+        out.execute(Print(health))?;
+        Ok(())
+    }
+}
+
+use std::collections::VecDeque;
+
+// Rolling history of gameplay events, newest at the bottom, so the player can
+// see what happened a few turns back instead of just the most recent line.
+struct MessageLog {
+    entries: VecDeque<(String, Colors)>,
+    capacity: usize,
+}
+impl MessageLog {
+    fn new(capacity: usize) -> Self {
+        MessageLog { entries: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    fn push(&mut self, text: impl Into<String>, colors: Colors) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((text.into(), colors));
+    }
+
+    // Renders the last `ROWS` entries into the reserved log area starting at `start_row`,
+    // newest entry on the bottom row, padding any unused rows with blank lines.
+    fn draw(&self, out: &mut std::io::Stdout, start_row: u16) -> std::io::Result<()> {
+        use crossterm::{cursor, style::{SetColors, Print}, QueueableCommand};
+        const ROWS: u16 = 4;
+        let blank = Colors{foreground:None, background:None};
+        for row in 0..ROWS {
+            out.queue(cursor::MoveTo(0, start_row + row))?;
+            match self.entries.iter().rev().nth((ROWS - 1 - row) as usize) {
+                Some((text, colors)) => {
+                    out.queue(SetColors(*colors))?;
+                    let mut line = text.clone();
+                    if line.len() < 80 {
+                        line.push_str(&" ".repeat(80 - line.len()));
+                    }
+                    out.queue(Print(line))?;
+                }
+                None => {
+                    out.queue(SetColors(blank))?;
+                    out.queue(Print(" ".repeat(80)))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
 
 use crossterm::{style::{Color, Colors}, ExecutableCommand};
 trait Style {
@@ -57,7 +181,7 @@ impl Style for ThingType {
     fn colors(&self) -> Colors {
         match self {
             ThingType::Prisoner => Colors{foreground:Some(Color::Green), background:Some(Color::Black)},
-            ThingType::Guard => Colors{foreground:Some(Color::Red), background:Some(Color::Black)},
+            ThingType::Guard => Colors{foreground:Some(Color::DarkRed), background:Some(Color::Black)},
         }
     }
     fn look(&self) -> char {
@@ -68,6 +192,40 @@ impl Style for ThingType {
     }
 }
 
+// One row of a symmetric-shadowcasting scan: `depth` tiles out from the origin,
+// bounded by `start_slope`/`end_slope` (narrowed by walls as the scan recurses).
+struct ShadowRow {
+    depth: i32,
+    start_slope: f64,
+    end_slope: f64,
+}
+impl ShadowRow {
+    fn next(&self) -> ShadowRow {
+        ShadowRow { depth: self.depth + 1, start_slope: self.start_slope, end_slope: self.end_slope }
+    }
+
+    // A tile is symmetric (visible from both ends of the line between it and the
+    // origin) only if it falls within both of this row's slope bounds.
+    fn is_symmetric(&self, col: i32) -> bool {
+        let col = col as f64;
+        col >= self.depth as f64 * self.start_slope && col <= self.depth as f64 * self.end_slope
+    }
+}
+
+// The slope to a tile's near edge (the edge facing the origin's side of the scan),
+// used both to tighten a row's `start_slope` after a wall-to-floor transition and
+// to cap the next row's `end_slope` after a floor-to-wall transition.
+fn shadow_slope(depth: i32, col: i32) -> f64 {
+    (2 * col - 1) as f64 / (2 * depth) as f64
+}
+
+fn round_ties_up(n: f64) -> i32 {
+    (n + 0.5).floor() as i32
+}
+fn round_ties_down(n: f64) -> i32 {
+    (n - 0.5).ceil() as i32
+}
+
 impl Map {
     fn draw(&self, out:&mut std::io::Stdout) -> std::io::Result<()> {
         // We can scope a use just to a single function, which is nice
@@ -84,7 +242,12 @@ impl Map {
         for ent in self.entities.iter() {
             let (x,y) = ent.position;
             out.queue(cursor::MoveTo(x as u16,y as u16))?;
-            out.queue(SetColors(ent.thing_type.colors()))?;
+            let mut colors = ent.thing_type.colors();
+            if ent.alerted {
+                // An alerted guard has spotted the player, so it's drawn brighter.
+                colors.foreground = Some(Color::Red);
+            }
+            out.queue(SetColors(colors))?;
             out.queue(Print(ent.thing_type.look()))?;
         }
         out.queue(crossterm::terminal::EndSynchronizedUpdate)?;
@@ -92,6 +255,79 @@ impl Map {
         Ok(())
     }
 
+    // Flood fill out from `from`, treating walls and doors as impassable, so that
+    // guards can greedily descend the resulting distance field toward the player.
+    // Unreachable cells (behind walls, on the far side of a door) are left at u16::MAX.
+    fn dijkstra_map(&self, from: (u8, u8)) -> [[u16; 81]; 23] {
+        let mut dist = [[u16::MAX; 81]; 23];
+        let (fx, fy) = from;
+        dist[fy as usize][fx as usize] = 0;
+        let mut queue = std::collections::VecDeque::new();
+        queue.push_back((fx, fy));
+        while let Some((x, y)) = queue.pop_front() {
+            let d = dist[y as usize][x as usize];
+            for (dx, dy) in [(-1i8, 0i8), (1, 0), (0, -1), (0, 1)] {
+                let nx = x as i16 + dx as i16;
+                let ny = y as i16 + dy as i16;
+                if !(0_i16..81).contains(&nx) || !(0_i16..23).contains(&ny) {
+                    continue;
+                }
+                let (nx, ny) = (nx as u8, ny as u8);
+                if let Tile::Wall | Tile::Door(_) = self.tiles[ny as usize][nx as usize] {
+                    continue;
+                }
+                if dist[ny as usize][nx as usize] == u16::MAX {
+                    dist[ny as usize][nx as usize] = d + 1;
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+        dist
+    }
+
+    // Drains and applies one queued `Action` per entity, in entity order, so the
+    // player's move is resolved before guards act on it. Returns the indices of
+    // any entities attacked this tick so the caller can apply consequences.
+    fn step(&mut self) -> Vec<usize> {
+        let mut attacked = Vec::new();
+        for i in 0..self.entities.len() {
+            let Some(action) = self.entities[i].actions.pop_front() else { continue };
+            match action {
+                Action::Move(dx, dy) => {
+                    self.move_entity(i, dx, dy);
+                }
+                Action::Follow(target) => {
+                    let Some(target_pos) = self.entities.get(target).map(|t| t.position) else { continue };
+                    if self.entities[i].position != target_pos {
+                        let dist = self.dijkstra_map(target_pos);
+                        let (x, y) = self.entities[i].position;
+                        let mut best: Option<((i8, i8), u16)> = None;
+                        for (dx, dy) in [(-1i8, 0i8), (1, 0), (0, -1), (0, 1)] {
+                            let nx = x as i16 + dx as i16;
+                            let ny = y as i16 + dy as i16;
+                            if !(0_i16..81).contains(&nx) || !(0_i16..23).contains(&ny) {
+                                continue;
+                            }
+                            let d = dist[ny as usize][nx as usize];
+                            if best.is_none_or(|(_, best_d)| d < best_d) {
+                                best = Some(((dx, dy), d));
+                            }
+                        }
+                        if let Some(((dx, dy), d)) = best
+                            && d != u16::MAX
+                        {
+                            self.move_entity(i, dx, dy);
+                        }
+                    }
+                    if self.entities[i].position == target_pos {
+                        attacked.push(target);
+                    }
+                }
+            }
+        }
+        attacked
+    }
+
     fn move_entity(&mut self, which: usize, dx: i8, dy: i8) -> bool {
         let (x, y) = self.entities[which].position;
         let to_x = x as i16 + dx as i16;
@@ -105,9 +341,197 @@ impl Map {
         self.entities[which].position = (to_x as u8, to_y as u8);
         true
     }
+
+    // Looks up the flavor text/destination for a door tile's id, from the level file or a generated cave.
+    fn door(&self, id:DoorID) -> Option<&DoorMeta> {
+        self.doors.iter().find(|d| d.id == id)
+    }
+
+    // Builds a playable cave level: carve interior noise, smooth it into organic rooms
+    // and corridors, then drop a key and its matching door somewhere reachable from the
+    // canonical spawn point at the center of the map. Sealed-pocket generations (too few
+    // tiles reachable from spawn to place a key and door) are discarded and retried so the
+    // level is always winnable.
+    fn generate_cave(rng: &mut impl rand::Rng) -> [[Tile; 81]; 23] {
+        const SPAWN: (u8, u8) = (40, 11);
+        loop {
+            let mut tiles = [[Tile::Wall; 81]; 23];
+            for row in tiles.iter_mut().take(22).skip(1) {
+                for tile in row.iter_mut().take(80).skip(1) {
+                    *tile = if rng.gen_bool(0.45) { Tile::Wall } else { Tile::Empty };
+                }
+            }
+            for _ in 0..5 {
+                tiles = Self::smooth_cave(&tiles);
+            }
+            tiles[SPAWN.1 as usize][SPAWN.0 as usize] = Tile::Empty;
+
+            let reachable: Vec<(u8, u8)> = Self::flood_fill_empty(&tiles, SPAWN)
+                .into_iter()
+                .filter(|&pos| pos != SPAWN)
+                .collect();
+            if reachable.len() < 2 {
+                continue;
+            }
+            let key_pos = reachable[rng.gen_range(0..reachable.len())];
+            let door_pos = loop {
+                let candidate = reachable[rng.gen_range(0..reachable.len())];
+                if candidate != key_pos {
+                    break candidate;
+                }
+            };
+            tiles[key_pos.1 as usize][key_pos.0 as usize] = Tile::Key(DoorID(0));
+            tiles[door_pos.1 as usize][door_pos.0 as usize] = Tile::Door(DoorID(0));
+            return tiles;
+        }
+    }
+
+    // One generation of the "5 or more wall neighbors survive" cellular automaton rule,
+    // used to turn uniform noise into cave-like rooms and corridors. Borders always stay wall.
+    fn smooth_cave(tiles: &[[Tile; 81]; 23]) -> [[Tile; 81]; 23] {
+        let mut next = *tiles;
+        for (y, row) in next.iter_mut().enumerate() {
+            for (x, tile) in row.iter_mut().enumerate() {
+                if y == 0 || y == 22 || x == 0 || x == 80 {
+                    *tile = Tile::Wall;
+                    continue;
+                }
+                let mut wall_neighbors = 0;
+                for dy in -1i8..=1 {
+                    for dx in -1i8..=1 {
+                        if dx == 0 && dy == 0 {
+                            continue;
+                        }
+                        let nx = x as i16 + dx as i16;
+                        let ny = y as i16 + dy as i16;
+                        if !(0_i16..81).contains(&nx) || !(0_i16..23).contains(&ny) {
+                            wall_neighbors += 1;
+                            continue;
+                        }
+                        if let Tile::Wall = tiles[ny as usize][nx as usize] {
+                            wall_neighbors += 1;
+                        }
+                    }
+                }
+                *tile = if wall_neighbors >= 5 { Tile::Wall } else { Tile::Empty };
+            }
+        }
+        next
+    }
+
+    // Flood fill over anything that isn't a wall, used to check reachability from `from`
+    // so procedurally scattered keys/doors never end up in a sealed pocket.
+    fn flood_fill_empty(tiles: &[[Tile; 81]; 23], from: (u8, u8)) -> std::collections::HashSet<(u8, u8)> {
+        let mut seen = std::collections::HashSet::new();
+        let mut queue = std::collections::VecDeque::new();
+        seen.insert(from);
+        queue.push_back(from);
+        while let Some((x, y)) = queue.pop_front() {
+            for (dx, dy) in [(-1i8, 0i8), (1, 0), (0, -1), (0, 1)] {
+                let nx = x as i16 + dx as i16;
+                let ny = y as i16 + dy as i16;
+                if !(0_i16..81).contains(&nx) || !(0_i16..23).contains(&ny) {
+                    continue;
+                }
+                let (nx, ny) = (nx as u8, ny as u8);
+                if let Tile::Wall = tiles[ny as usize][nx as usize] {
+                    continue;
+                }
+                if seen.insert((nx, ny)) {
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+        seen
+    }
+
+    // Recursive field-of-view out to `radius` tiles, following Albert Ford's
+    // "symmetric shadowcasting" (https://www.albertford.com/shadowcasting/). The 8
+    // octants each scan outward in (depth, col) space; a wall narrows the row's
+    // start/end slopes for its children, and a tile is revealed only if it's a wall
+    // itself or falls within *both* endpoints' slope bounds (`is_symmetric`). That
+    // symmetric check is what guarantees `visible_from(a).contains(b) ==
+    // visible_from(b).contains(a)`.
+    fn visible_from(&self, origin: (u8, u8), radius: u8) -> std::collections::HashSet<(u8, u8)> {
+        let mut visible = std::collections::HashSet::new();
+        visible.insert(origin);
+        for octant in 0..8u8 {
+            let row = ShadowRow { depth: 1, start_slope: -1.0, end_slope: 1.0 };
+            self.scan_row(origin, radius as i32, octant, row, &mut visible);
+        }
+        visible
+    }
+
+    // Maps an octant's local (depth, col) coordinates -- depth counting outward from
+    // the origin, col the perpendicular offset -- onto a signed (dx, dy) offset.
+    fn octant_transform(octant: u8, depth: i32, col: i32) -> (i32, i32) {
+        match octant {
+            0 => (col, -depth),
+            1 => (depth, -col),
+            2 => (depth, col),
+            3 => (col, depth),
+            4 => (-col, depth),
+            5 => (-depth, col),
+            6 => (-depth, -col),
+            7 => (-col, -depth),
+            _ => unreachable!(),
+        }
+    }
+
+    // Out-of-bounds tiles block light (so a scan doesn't wrap past the map edge)
+    // but are never themselves revealed.
+    fn is_wall_at(&self, x: i32, y: i32) -> bool {
+        if !(0..81).contains(&x) || !(0..23).contains(&y) {
+            return true;
+        }
+        matches!(self.tiles[y as usize][x as usize], Tile::Wall)
+    }
+
+    fn scan_row(
+        &self,
+        origin: (u8, u8),
+        radius: i32,
+        octant: u8,
+        mut row: ShadowRow,
+        visible: &mut std::collections::HashSet<(u8, u8)>,
+    ) {
+        if row.depth > radius {
+            return;
+        }
+        let (ox, oy) = (origin.0 as i32, origin.1 as i32);
+        let min_col = round_ties_up(row.depth as f64 * row.start_slope);
+        let max_col = round_ties_down(row.depth as f64 * row.end_slope);
+        let mut prev_wall: Option<bool> = None;
+        for col in min_col..=max_col {
+            let (dx, dy) = Self::octant_transform(octant, row.depth, col);
+            let (x, y) = (ox + dx, oy + dy);
+            let wall = self.is_wall_at(x, y);
+            if (wall || row.is_symmetric(col))
+                && (0..81).contains(&x)
+                && (0..23).contains(&y)
+                && dx * dx + dy * dy <= radius * radius
+            {
+                visible.insert((x as u8, y as u8));
+            }
+            if let Some(prev_wall) = prev_wall {
+                if prev_wall && !wall {
+                    row.start_slope = shadow_slope(row.depth, col);
+                }
+                if !prev_wall && wall {
+                    let mut next_row = row.next();
+                    next_row.end_slope = shadow_slope(row.depth, col);
+                    self.scan_row(origin, radius, octant, next_row, visible);
+                }
+            }
+            prev_wall = Some(wall);
+        }
+        if prev_wall == Some(false) {
+            self.scan_row(origin, radius, octant, row.next(), visible);
+        }
+    }
 }
 
-fn parse_tilemap<const W:usize, const H:usize>(text:&'static str) -> [[Tile; W] ; H] {
+fn parse_tilemap<const W:usize, const H:usize>(text:&str) -> [[Tile; W] ; H] {
     let mut ret = [[Tile::Empty; W]; H];
     let chars:Vec<_> = text.chars().collect();
     for (y,row) in chars.chunks(W).enumerate() {
@@ -129,73 +553,243 @@ fn parse_tilemap<const W:usize, const H:usize>(text:&'static str) -> [[Tile; W]
     ret
 }
 
+// Directory holding the authored campaign's level files, read at runtime so that
+// community-made prison layouts can be dropped in without recompiling.
+const LEVEL_DIR: &str = "levels";
 
+// Parses one level file: a small header of SPAWN/DOOR directives, a `MAP` marker, then
+// the 81x23 glyph grid itself (same glyphs `parse_tilemap` already understands). This is
+// our REX-Paint-inspired format -- a layer of gameplay metadata stacked on top of the
+// plain tile art -- and it's what replaces the old `include_str!` + hardcoded entity lists.
+fn load_level(path: &std::path::Path) -> std::io::Result<Map> {
+    let text = std::fs::read_to_string(path)?;
+    let err = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {msg}", path.display()));
+    // Parses an "x y" pair and rejects anything outside the 81x23 tile grid, so a
+    // community-authored level can't plant a spawn or door destination somewhere that
+    // would later index `Map::tiles` out of bounds.
+    let parse_grid_pos = |x: &str, y: &str, what: &str| -> std::io::Result<(u8, u8)> {
+        let x: u8 = x.parse().map_err(|_| err(&format!("bad {what} x")))?;
+        let y: u8 = y.parse().map_err(|_| err(&format!("bad {what} y")))?;
+        if x as usize >= 81 || y as usize >= 23 {
+            return Err(err(&format!("{what} position ({x}, {y}) is outside the 81x23 grid")));
+        }
+        Ok((x, y))
+    };
+
+    let mut lines = text.lines();
+    let mut entities = Vec::new();
+    let mut doors = Vec::new();
+    for line in &mut lines {
+        if line == "MAP" {
+            break;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            ["SPAWN", "PRISONER", x, y] => {
+                let pos = parse_grid_pos(x, y, "spawn")?;
+                entities.push(Thing::new(ThingType::Prisoner, pos));
+            }
+            ["SPAWN", "GUARD", x, y] => {
+                let pos = parse_grid_pos(x, y, "spawn")?;
+                entities.push(Thing::new(ThingType::Guard, pos));
+            }
+            ["DOOR", id, "EXIT"] => {
+                let id = DoorID(id.parse().map_err(|_| err("bad door id"))?);
+                doors.push(DoorMeta{id, kind:DoorKind::Exit});
+            }
+            ["DOOR", id, "PASSAGE", x, y, message @ ..] => {
+                let id = DoorID(id.parse().map_err(|_| err("bad door id"))?);
+                let destination = parse_grid_pos(x, y, "door destination")?;
+                doors.push(DoorMeta{id, kind:DoorKind::Passage{message:message.join(" "), destination}});
+            }
+            [] => {}
+            _ => return Err(err("unrecognized header line")),
+        }
+    }
+    let grid_text: String = lines.collect();
+    let tiles: [[Tile; 81]; 23] = parse_tilemap(&grid_text);
+    Ok(Map { tiles: tiles.map(TileRow), entities, doors })
+}
+
+// Loads every level file in `dir`, in filename order, to build the authored campaign's `World`.
+fn load_levels(dir: &std::path::Path) -> std::io::Result<Vec<Map>> {
+    let mut paths: Vec<_> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect();
+    paths.sort();
+    paths.iter().map(|path| load_level(path)).collect()
+}
+
+// Generates a procedural cave level and populates it with guards (and, on the first
+// level of an endless run, the player), scattered over its flood-fill-reachable tiles.
+fn build_cave_map(rng: &mut impl rand::Rng, spawn_player: bool) -> Map {
+    const SPAWN: (u8, u8) = (40, 11);
+    let tiles = Map::generate_cave(rng);
+    let reachable: Vec<(u8, u8)> = Map::flood_fill_empty(&tiles, SPAWN)
+        .into_iter()
+        .filter(|&pos| pos != SPAWN)
+        .collect();
+    let mut entities = Vec::new();
+    if spawn_player {
+        entities.push(Thing::new(ThingType::Prisoner, SPAWN));
+    }
+    for _ in 0..4 {
+        if reachable.is_empty() {
+            break;
+        }
+        let idx = rng.gen_range(0..reachable.len());
+        entities.push(Thing::new(ThingType::Guard, reachable[idx]));
+    }
+    let doors = vec![DoorMeta{
+        id:DoorID(0),
+        kind:DoorKind::Passage{
+            message:"You press deeper into the ever-shifting prison".to_string(),
+            destination:SPAWN,
+        },
+    }];
+    Map { tiles: tiles.map(TileRow), entities, doors }
+}
+
+
+
+const SAVE_FILE: &str = "savegame.json";
+const GUARD_VISION_RADIUS: u8 = 6;
+
+// Drains and resolves exactly one queued action for every entity on the current map --
+// the player's next banked move and whatever each guard decided last turn -- then applies
+// the consequences: guards that spot the player raise the alarm, guards that catch up to
+// the player attack, and the player's new tile may open a door or hand over a key. Returns
+// true once the game has ended (victory or death), so a caller draining several banked
+// player moves in a row knows to stop early.
+fn resolve_turn(
+    world: &mut World,
+    prisoner_state: &mut PrisonerState,
+    log: &mut MessageLog,
+    rng: &mut impl rand::Rng,
+    endless_mode: bool,
+) -> bool {
+    let map = &mut world.maps[world.current_map];
+    let attacked = map.step();
+    // Remember where the player is now...
+    let (x, y) = map.entities[0].position;
+    // A guard that spots the player along an unobstructed line raises the alarm
+    // and never stands down; alerted guards queue a Follow toward the player so
+    // they chase it down on their next turn.
+    for ent in 1..map.entities.len() {
+        if !map.entities[ent].alerted
+            && map.visible_from(map.entities[ent].position, GUARD_VISION_RADIUS).contains(&(x, y))
+        {
+            map.entities[ent].alerted = true;
+            log.push("A guard spotted you!", Colors{foreground:Some(Color::Red), background:Some(Color::White)});
+        }
+        if map.entities[ent].alerted {
+            map.entities[ent].actions.push_back(Action::Follow(0));
+        }
+    }
+    let mut game_over = false;
+    // each guard that attacked the player this turn costs 50 health; at 0 health, game over
+    for _ in attacked.iter().filter(|&&target| target == 0) {
+        prisoner_state.health -= 50;
+        let hit_colors = Colors{foreground:Some(Color::Red), background:Some(Color::White)};
+        if prisoner_state.health == 0 {
+            log.push("You died! Game Over", hit_colors);
+            game_over = true;
+        } else {
+            log.push("A guard hit you", hit_colors);
+        }
+    }
+    // Maybe move between rooms
+    if let Tile::Door(door_id) = map.tiles[y as usize][x as usize] {
+        let door_colors = Colors{foreground:Some(Color::Red), background:Some(Color::White)};
+        match map.door(door_id).cloned() {
+            Some(DoorMeta{kind:DoorKind::Exit, ..}) => {
+                log.push("You made it out! Enjoy your freedom", Colors{foreground:Some(Color::DarkGreen), background:Some(Color::White)});
+                game_over = true;
+            }
+            Some(DoorMeta{kind:DoorKind::Passage{message, destination}, ..}) if prisoner_state.keys.contains(&door_id) => {
+                world.current_map += 1;
+                // move player to new room
+                let mut player = map.entities.remove(0);
+                // The prison never ends: generate the next cave level on demand.
+                if endless_mode && world.current_map >= world.maps.len() {
+                    world.maps.push(build_cave_map(rng, false));
+                }
+                log.push(message, door_colors);
+                player.position = destination;
+                world.maps[world.current_map].entities.insert(0, player);
+            }
+            Some(DoorMeta{kind:DoorKind::Passage{..}, ..}) => {
+                log.push("You need a key or the right key to open this door!", door_colors);
+            }
+            None => {
+                // A door tile with no matching header entry: a level-authoring mistake,
+                // but we'd rather surface it than have the player bump into it silently.
+                log.push("This door leads nowhere...", door_colors);
+            }
+        }
+    }
+    // maybe get a key
+    else if let Tile::Key(door_id) = map.tiles[y as usize][x as usize] {
+        prisoner_state.keys.push(door_id);
+        log.push("You collected a key!", Colors{foreground:Some(Color::Red), background:Some(Color::White)});
+        // remove key from map
+        map.tiles[y as usize][x as usize] = Tile::Empty
+    }
+    game_over
+}
 
 fn main() -> std::io::Result<()> {
     use std::io::stdout;
     use crossterm::event::{read, Event, KeyEvent, KeyEventKind, KeyCode};
-    use rand::Rng;
     use crossterm::terminal;
 
     let mut rng = rand::thread_rng();
     let mut stdout = stdout();
     {
         terminal::enable_raw_mode()?;
-        stdout.execute(crossterm::terminal::SetSize(80,27))?;
+        stdout.execute(crossterm::terminal::SetSize(80,29))?;
         stdout.execute(crossterm::cursor::Hide)?;
         stdout.execute(terminal::Clear(terminal::ClearType::All))?;
     }
 
-    let mut world = World {
-        maps:vec![
-            Map {
-                tiles:parse_tilemap(include_str!("map0.txt")),
-                entities:vec![
-                    Thing{thing_type:ThingType::Prisoner, position:(53,7)},
-                    Thing{thing_type:ThingType::Guard, position:(21,15)},
-                    Thing{thing_type:ThingType::Guard, position:(65,11)},
-                    Thing{thing_type:ThingType::Guard, position:(74,20)},
-                    Thing{thing_type:ThingType::Guard, position:(8,19)},
-                ]
-            },
-            Map {
-                tiles:parse_tilemap(include_str!("map1.txt")),
-                entities:vec![
-                    Thing{thing_type:ThingType::Guard, position:(7,9)},
-                    Thing{thing_type:ThingType::Guard, position:(76,14)},
-                    Thing{thing_type:ThingType::Guard, position:(39,15)},
-                ]
-            },
-            Map {
-                tiles:parse_tilemap(include_str!("map2.txt")),
-                entities:vec![
-                    Thing{thing_type:ThingType::Guard, position:(47,9)},
-                    Thing{thing_type:ThingType::Guard, position:(69,13)},
-                    Thing{thing_type:ThingType::Guard, position:(19,22)},
-                    Thing{thing_type:ThingType::Guard, position:(29,3)},
-                ]
+    // Let the player pick the authored campaign or an endless procedurally generated prison.
+    stdout.execute(crossterm::style::SetColors(Colors{foreground:Some(Color::White), background:Some(Color::Black)}))?;
+    stdout.execute(crossterm::cursor::MoveTo(0,0))?;
+    stdout.execute(crossterm::style::Print("Choose a mode:  [1] Authored campaign   [2] Endless procedural prison"))?;
+    let endless_mode = loop {
+        if let Event::Key(KeyEvent{code,kind:KeyEventKind::Press,..}) = read()? {
+            match code {
+                KeyCode::Char('1') => break false,
+                KeyCode::Char('2') => break true,
+                KeyCode::Esc => {
+                    terminal::disable_raw_mode()?;
+                    stdout.execute(crossterm::cursor::Show)?;
+                    return Ok(());
+                }
+                _ => {}
             }
-        ],
-        current_map:0
+        }
+    };
+    stdout.execute(terminal::Clear(terminal::ClearType::All))?;
+
+    let mut world = if endless_mode {
+        World { maps:vec![build_cave_map(&mut rng, true)], current_map:0 }
+    } else {
+        World { maps:load_levels(std::path::Path::new(LEVEL_DIR))?, current_map:0 }
     };
     let mut game_over = false;
     let mut prisoner_state: PrisonerState = PrisonerState{keys: [].to_vec(), health: 100};
+    let mut log = MessageLog::new(50);
+    let instructions = Colors{foreground:Some(Color::Black), background:Some(Color::White)};
+    log.push("Escape the Prison!", instructions);
+    log.push("Collect keys * to open doors > Don't get caught by the guards G!", instructions);
+    log.push("You are currently in your cell. Goodluck!", instructions);
+    log.push("Press s to save your progress, l to load it back", instructions);
+    log.push("Arrow keys queue up moves; press Enter to act them out", instructions);
     // One initial draw so that we have something on screen before the first event arrives.
     world.maps[world.current_map].draw(&mut stdout)?;
-
-    // print instructions
-    stdout.execute(crossterm::cursor::MoveTo(0, 23))?;
-    stdout.execute(crossterm::style::SetColors(Colors{foreground:Some(Color::Black), background:Some(Color::White)}))?;
-    let instruction1 = "Escape the Prison! ";
-    stdout.execute(crossterm::style::Print(instruction1))?;
-    stdout.execute(crossterm::cursor::MoveTo(0, 24))?;
-    stdout.execute(crossterm::style::SetColors(Colors{foreground:Some(Color::Black), background:Some(Color::White)}))?;
-    let instruction1 = "Collect keys * to open doors > Don't get caught by the guards G!";
-    stdout.execute(crossterm::style::Print(instruction1))?;
-    stdout.execute(crossterm::cursor::MoveTo(0, 25))?;
-    stdout.execute(crossterm::style::SetColors(Colors{foreground:Some(Color::Black), background:Some(Color::White)}))?;
-    let instruction1 = "You are currently in your cell. Goodluck!";
-    stdout.execute(crossterm::style::Print(instruction1))?;
+    log.draw(&mut stdout, 23)?;
 
     // ... event loop and everything else goes here...
     // Get the next event from crossterm, waiting until it's ready
@@ -204,94 +798,62 @@ fn main() -> std::io::Result<()> {
             if code == KeyCode::Esc {
                 break;
             }
-            if game_over { continue; }
-            let mut status_message = (
-                "                                                                                ",
-                Colors{foreground:None, background:None}
-            );
-            // Game rule updates: first, interpret key events.
-            let (dx,dy) = match code {
-                KeyCode::Left => (-1, 0),
-                KeyCode::Right => (1, 0),
-                KeyCode::Up => (0, -1),
-                KeyCode::Down => (0, 1),
-                _ => (0,0)
-            };
-            // Get the current map from the world
-            let map = &mut world.maps[world.current_map];
-            // Ask it to move our player.  We'll read through this function's code later.
-            map.move_entity(0, dx, dy);
-            // Then loop through all the other entities and have them move randomly
-            for ent in 1..map.entities.len() {
-                let dx:i8 = rng.gen_range(-2..=2);
-                let dy:i8 = rng.gen_range(-2..=2);
-                map.move_entity(ent, dx, dy);
-            }
-            // Remember where the player is now...
-            let (x,y) = map.entities[0].position;
-            // if any enemy is touching the player, game over
-            for ent in map.entities[1..].iter() {
-                if let ThingType::Guard = ent.thing_type {
-                    if ent.position == (x,y) {
-                        prisoner_state.health -= 50;
-                        if prisoner_state.health == 0 {
-                            // Set a status message to render later
-                            status_message = ("You died! Game Over", Colors{foreground:Some(Color::Red), background:Some(Color::White)});
-                            game_over = true;
-                        } else {
-                            status_message = ("A guard hit you", Colors{foreground:Some(Color::Red), background:Some(Color::White)});
-                        }
-                        
+            if code == KeyCode::Char('s') {
+                match serde_json::to_string(&(&world, &prisoner_state)) {
+                    Ok(json) if std::fs::write(SAVE_FILE, &json).is_ok() => {
+                        log.push("Game saved", instructions);
                     }
+                    _ => log.push("Failed to save game", instructions),
                 }
+                log.draw(&mut stdout, 23)?;
+                continue;
             }
-            // Maybe move between rooms
-            if let Tile::Door(door_id       ) = map.tiles[y as usize][x as usize] {
-                if door_id == DoorID(3) {
-                    status_message = ("You made it out! Enjoy your freedom          ", Colors{foreground:Some(Color::DarkGreen), background:Some(Color::White)});
-                    game_over = true;
-                }
-                else if prisoner_state.keys.contains(&door_id) {
-                    world.current_map = world.current_map + 1;
-                    // move player to new room
-                    let mut player = map.entities.remove(0);
-                    if door_id == DoorID(1) {
-                        status_message = ("You are entering the infirmary              ", Colors{foreground:Some(Color::Red), background:Some(Color::White)});
-                        player.position = (6,0);
-                    } else if door_id == DoorID(0) {
-                        status_message = ("You are entering the cafeteria              ", Colors{foreground:Some(Color::Red), background:Some(Color::White)});
-                        player.position = (74,0);
-                    } else {
-                        status_message = ("Find your way through the tunnels                   ", Colors{foreground:Some(Color::Red), background:Some(Color::White)});
-                        player.position = (40,0);
+            if code == KeyCode::Char('l') {
+                match std::fs::read_to_string(SAVE_FILE)
+                    .ok()
+                    .and_then(|json| serde_json::from_str::<(World, PrisonerState)>(&json).ok())
+                {
+                    Some((loaded_world, loaded_state)) => {
+                        world = loaded_world;
+                        prisoner_state = loaded_state;
+                        game_over = false;
+                        log.push("Game loaded", instructions);
+                        world.maps[world.current_map].draw(&mut stdout)?;
+                        prisoner_state.draw_footer(&mut stdout)?;
                     }
-                    world.maps[world.current_map].entities.insert(0,player);
-                } else {
-                    status_message = ("You need a key or the right key to open this door!          ", Colors{foreground:Some(Color::Red), background:Some(Color::White)});
+                    None => log.push("Failed to load game", instructions),
                 }
-                
+                log.draw(&mut stdout, 23)?;
+                continue;
             }
-            // maybe get a key
-            else if let Tile::Key(door_id       ) = map.tiles[y as usize][x as usize] {
-                prisoner_state.keys.push(door_id);
-                status_message = ("You collected a key!                   ", Colors{foreground:Some(Color::Red), background:Some(Color::White)});
-                // remove key from map
-                map.tiles[y as usize][x as usize] = Tile::Empty
+            if game_over { continue; }
+            // Arrow keys bank a move onto the player's queue without acting it out yet,
+            // so several can be queued up in a row; Enter drains the whole queue, one
+            // resolve_turn per banked move, stopping early if the game ends partway through.
+            let queued_move = match code {
+                KeyCode::Left => Some(Action::Move(-1, 0)),
+                KeyCode::Right => Some(Action::Move(1, 0)),
+                KeyCode::Up => Some(Action::Move(0, -1)),
+                KeyCode::Down => Some(Action::Move(0, 1)),
+                _ => None,
+            };
+            if let Some(action) = queued_move {
+                world.maps[world.current_map].entities[0].actions.push_back(action);
+                log.push("Move queued", instructions);
+                log.draw(&mut stdout, 23)?;
+                continue;
             }
-            // Update's done; render the game state.
-            world.maps[world.current_map].draw(&mut stdout)?;
-            {
-                stdout.execute(crossterm::cursor::MoveTo(0, 23))?;
-                stdout.execute(crossterm::style::SetColors(Colors{foreground:Some(Color::Black), background:Some(Color::White)}))?;
-                let inventory = "Inventory: ".to_string() + &(prisoner_state.keys.len()).to_string() + " keys                                                               ";
-                stdout.execute(crossterm::style::Print(inventory))?;
-                stdout.execute(crossterm::cursor::MoveTo(0, 24))?;
-                stdout.execute(crossterm::style::SetColors(Colors{foreground:Some(Color::Black), background:Some(Color::White)}))?;
-                let inventory = "Health: ".to_string() + &(prisoner_state.health).to_string() + "%                                                                     ";
-                stdout.execute(crossterm::style::Print(inventory))?;
-                stdout.execute(crossterm::cursor::MoveTo(0, 25))?;
-                stdout.execute(crossterm::style::SetColors(status_message.1))?;
-                stdout.execute(crossterm::style::Print(status_message.0))?;
+            if code == KeyCode::Enter {
+                while !world.maps[world.current_map].entities[0].actions.is_empty() {
+                    game_over = resolve_turn(&mut world, &mut prisoner_state, &mut log, &mut rng, endless_mode);
+                    world.maps[world.current_map].draw(&mut stdout)?;
+                    log.draw(&mut stdout, 23)?;
+                    prisoner_state.draw_footer(&mut stdout)?;
+                    if game_over {
+                        world.maps[world.current_map].entities[0].actions.clear();
+                        break;
+                    }
+                }
             }
         }
     }
@@ -304,3 +866,48 @@ fn main() -> std::io::Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod fov_tests {
+    use super::*;
+
+    fn bordered_map(walls: &[(u8, u8)]) -> Map {
+        let mut tiles = [[Tile::Empty; 81]; 23];
+        for (y, row) in tiles.iter_mut().enumerate() {
+            for (x, tile) in row.iter_mut().enumerate() {
+                if y == 0 || y == 22 || x == 0 || x == 80 {
+                    *tile = Tile::Wall;
+                }
+            }
+        }
+        for &(x, y) in walls {
+            tiles[y as usize][x as usize] = Tile::Wall;
+        }
+        Map { tiles: tiles.map(TileRow), entities: Vec::new(), doors: Vec::new() }
+    }
+
+    // Regression test for a prior bug where the shadowcasting scan revealed a tile
+    // from one side of a wall but not the other, despite the whole point of using
+    // the symmetric variant of the algorithm being that visibility is reciprocal.
+    #[test]
+    fn visibility_is_symmetric_around_a_wall() {
+        let a = (10u8, 10u8);
+        let b = (16u8, 9u8);
+        for wall_configs in [
+            vec![(11u8, 10u8)],
+            vec![(12u8, 10u8)],
+            vec![(14u8, 9u8)],
+            vec![(13u8, 10u8), (13u8, 9u8)],
+        ] {
+            let map = bordered_map(&wall_configs);
+            let visible_from_a = map.visible_from(a, 8);
+            let visible_from_b = map.visible_from(b, 8);
+            assert_eq!(
+                visible_from_a.contains(&b),
+                visible_from_b.contains(&a),
+                "asymmetric visibility with walls at {:?}",
+                wall_configs
+            );
+        }
+    }
+}